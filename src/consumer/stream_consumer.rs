@@ -1,6 +1,6 @@
 //! Stream-based consumer implementation.
-use futures::{Future, Poll, Sink, Stream};
-use futures::sync::mpsc;
+use futures::{Async, Future, Poll, Stream};
+use futures::task::{self, Task};
 use rdsys::types::*;
 use rdsys;
 
@@ -9,29 +9,150 @@ use consumer::base_consumer::BaseConsumer;
 use consumer::{Consumer, ConsumerContext, EmptyConsumerContext};
 use error::{KafkaError, KafkaResult};
 use message::Message;
-use util::duration_to_millis;
-
-use std::cell::Cell;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::thread::{self, JoinHandle};
-use std::time::Duration;
-
-
-/// A Consumer with an associated polling thread. This consumer doesn't need to
-/// be polled and it will return all consumed messages as a `Stream`.
-/// Due to the asynchronous nature of the stream, some messages might be consumed by the consumer
-/// without being processed on the other end of the stream. If auto commit is used, it might cause
-/// message loss after consumer restart. Manual offset storing should be used, see the `store_offset`
-/// function on `Consumer`.
-#[must_use = "Consumer polling thread will stop immediately if unused"]
-pub struct StreamConsumer<C: ConsumerContext + 'static> {
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Poll timeout used while draining the consumer queue in `StreamConsumer::close`.
+const CLOSE_DRAIN_POLL_INTERVAL_MS: i32 = 100;
+
+/// Default bound (in milliseconds) on how long `StreamConsumer::close` (and, via `Drop`, every
+/// `StreamConsumer` teardown) will spend draining the consumer queue. Kept short because `close`
+/// runs on every `Drop`, including ones the caller expects to be near-instant; a consumer that's
+/// still under sustained delivery when dropped would otherwise stall the drop path for however
+/// long it takes the queue to actually go quiet. Callers that need a more thorough drain can call
+/// `close_with_timeout` directly with a longer bound.
+const DEFAULT_CLOSE_DRAIN_TIMEOUT_MS: u64 = 500;
+
+
+/// Abstracts over the timer used to schedule work that doesn't fit into `MessageStream::poll`
+/// itself, such as the batching timer used by `start_batched` and the fallback re-poll described
+/// by `POLL_FALLBACK_INTERVAL_MS`. Implement this trait to back those timers with an existing
+/// tokio/async-std executor's timer wheel instead of `DefaultRuntime`'s dedicated timer thread.
+pub trait Runtime: Send + Sync + 'static {
+    /// A future that resolves once `duration` has elapsed.
+    type Delay: Future<Item = (), Error = ()> + Send + 'static;
+
+    /// Returns a future that resolves after `duration`.
+    fn delay_for(&self, duration: Duration) -> Self::Delay;
+}
+
+/// A pending `DefaultRuntime` timer, ordered by deadline so `DefaultRuntime`'s background thread
+/// can always find the next one due with a `BinaryHeap`.
+struct TimerEntry {
+    deadline: Instant,
+    sender: ::futures::sync::oneshot::Sender<()>,
+}
+
+impl PartialEq for TimerEntry {
+    fn eq(&self, other: &TimerEntry) -> bool {
+        self.deadline == other.deadline
+    }
+}
+
+impl Eq for TimerEntry {}
+
+impl PartialOrd for TimerEntry {
+    fn partial_cmp(&self, other: &TimerEntry) -> Option<::std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TimerEntry {
+    fn cmp(&self, other: &TimerEntry) -> ::std::cmp::Ordering {
+        self.deadline.cmp(&other.deadline)
+    }
+}
+
+/// The `Runtime` used by default: it preserves the crate's historical behavior of not depending
+/// on any particular async executor. Unlike spawning a dedicated OS thread per `delay_for` call
+/// (which `start_batched` and every `MessageStream`'s fallback re-poll call continuously), all
+/// `DefaultRuntime` clones sharing one `TimerEntry` heap and a single background thread that
+/// sleeps until the next deadline, so the per-timer cost is one heap push instead of one thread.
+#[derive(Clone)]
+pub struct DefaultRuntime {
+    timers: Arc<(Mutex<BinaryHeap<Reverse<TimerEntry>>>, Condvar)>,
+}
+
+impl Default for DefaultRuntime {
+    fn default() -> DefaultRuntime {
+        let timers = Arc::new((Mutex::new(BinaryHeap::new()), Condvar::new()));
+        spawn_timer_thread(Arc::clone(&timers));
+        DefaultRuntime { timers }
+    }
+}
+
+/// Runs on a single dedicated thread shared by every `DefaultRuntime` clone: sleeps until the
+/// earliest pending deadline, fires its sender, and repeats. `delay_for` wakes this thread early
+/// via the `Condvar` whenever it pushes a deadline sooner than whatever this thread is currently
+/// waiting on.
+fn spawn_timer_thread(timers: Arc<(Mutex<BinaryHeap<Reverse<TimerEntry>>>, Condvar)>) {
+    thread::spawn(move || {
+        let (lock, condvar) = &*timers;
+        let mut heap = lock.lock().unwrap();
+        loop {
+            match heap.pop() {
+                None => {
+                    heap = condvar.wait(heap).unwrap();
+                },
+                Some(Reverse(entry)) => {
+                    let now = Instant::now();
+                    if entry.deadline <= now {
+                        let _ = entry.sender.send(());
+                    } else {
+                        let wait = entry.deadline - now;
+                        heap.push(Reverse(entry));
+                        heap = condvar.wait_timeout(heap, wait).unwrap().0;
+                    }
+                },
+            }
+        }
+    });
+}
+
+impl Runtime for DefaultRuntime {
+    type Delay = ::futures::sync::oneshot::Receiver<()>;
+
+    fn delay_for(&self, duration: Duration) -> Self::Delay {
+        let (sender, receiver) = ::futures::sync::oneshot::channel();
+        let deadline = Instant::now() + duration;
+        let (lock, condvar) = &*self.timers;
+        let mut heap = lock.lock().unwrap();
+        let wakes_thread_early = heap.peek().map_or(true, |&Reverse(ref next)| deadline < next.deadline);
+        heap.push(Reverse(TimerEntry { deadline, sender }));
+        if wakes_thread_early {
+            condvar.notify_one();
+        }
+        receiver
+    }
+}
+
+/// A Consumer that exposes a `Stream` of messages. Unlike earlier iterations of this consumer,
+/// no background polling thread is involved: `MessageStream::poll` drives `poll_raw` directly,
+/// and liveness (`max.poll.interval.ms` / KIP-62) is therefore tied to the application actually
+/// consuming the stream, as librdkafka expects. If auto commit is used with the default,
+/// non-prefetching streams, a message is only consumed when the stream hands it to the
+/// application, so no messages can be lost on restart; streams with prefetching enabled (see
+/// `start_with_backpressure`) can drop already-consumed messages and do not give this guarantee.
+/// Manual offset storing can still be used, see the `store_offset` function on `Consumer`.
+pub struct StreamConsumer<C: ConsumerContext + 'static, R: Runtime = DefaultRuntime> {
+    // Field order matters here: Rust drops struct fields top to bottom, and `wakeup`'s `Drop`
+    // unhooks its queue callback, which must happen before `consumer` (and the queue it owns)
+    // is torn down, or a late event could invoke the callback on freed memory.
+    wakeup: Arc<WakeupContext>,
     consumer: Arc<BaseConsumer<C>>,
-    should_stop: Arc<AtomicBool>,
-    handle: Cell<Option<JoinHandle<()>>>,
+    next_stream_id: AtomicUsize,
+    runtime: R,
 }
 
-impl<C: ConsumerContext> Consumer<C> for StreamConsumer<C> {
+impl<C: ConsumerContext, R: Runtime> Consumer<C> for StreamConsumer<C, R> {
     fn get_base_consumer(&self) -> &BaseConsumer<C> {
         Arc::as_ref(&self.consumer)
     }
@@ -43,15 +164,90 @@ impl FromClientConfig for StreamConsumer<EmptyConsumerContext> {
     }
 }
 
-/// Creates a new `Consumer` starting from a `ClientConfig`.
+/// Creates a new `Consumer` starting from a `ClientConfig`, using the `DefaultRuntime`.
 impl<C: ConsumerContext> FromClientConfigAndContext<C> for StreamConsumer<C> {
     fn from_config_and_context(config: &ClientConfig, context: C) -> KafkaResult<StreamConsumer<C>> {
-        let stream_consumer = StreamConsumer {
-            consumer: Arc::new(BaseConsumer::from_config_and_context(config, context)?),
-            should_stop: Arc::new(AtomicBool::new(false)),
-            handle: Cell::new(None),
-        };
-        Ok(stream_consumer)
+        StreamConsumer::from_config_and_context_with_runtime(config, context, DefaultRuntime::default())
+    }
+}
+
+impl<C: ConsumerContext, R: Runtime> StreamConsumer<C, R> {
+    /// Creates a new `Consumer` starting from a `ClientConfig`, using the given `Runtime` to
+    /// schedule any work the consumer can't do inline from `MessageStream::poll`.
+    pub fn from_config_and_context_with_runtime(config: &ClientConfig, context: C, runtime: R) -> KafkaResult<StreamConsumer<C, R>> {
+        let consumer = Arc::new(BaseConsumer::from_config_and_context(config, context)?);
+        let wakeup = WakeupContext::new(&consumer);
+        Ok(StreamConsumer { consumer, wakeup, next_stream_id: AtomicUsize::new(0), runtime })
+    }
+}
+
+/// Holds the `Task` of every live `MessageStream`, keyed by stream id. A queue event wakes up
+/// every registered stream: the queue may hold more than one event, and a single `wakeup_cb`
+/// invocation doesn't tell us how many, so waking only one risks stranding the others with
+/// available data until the next edge-triggered event arrives — which, for an otherwise-idle
+/// queue, may be never. Waking everyone costs a redundant `poll_raw` from whichever streams don't
+/// get a message, but `MessageStream::poll_delay` (see `POLL_FALLBACK_INTERVAL_MS`) already bounds
+/// how often that can happen on its own, so the extra cost here is a single harmless re-poll per
+/// event, not a sustained spin.
+struct WakeupContext {
+    // The consumer's queue, kept around only so `Drop` can unhook `wakeup_cb` from it; not owned
+    // by `WakeupContext` (the underlying `BaseConsumer` owns the queue's lifetime).
+    queue: *mut RDKafkaQueue,
+    tasks: Mutex<HashMap<usize, Task>>,
+}
+
+// `queue` is only ever read to pass back to librdkafka and is valid for as long as the
+// `BaseConsumer` that outlives this `WakeupContext` (see the field order comment on
+// `StreamConsumer`).
+unsafe impl Send for WakeupContext {}
+unsafe impl Sync for WakeupContext {}
+
+impl WakeupContext {
+    // Build the `Arc` first and register *its* stable heap address with librdkafka: registering
+    // the address of `wakeup` before moving it into the `Arc` would hand librdkafka a pointer to
+    // a stack slot that's gone as soon as this function returns.
+    //
+    // This relies on `BaseConsumer::get_consumer_queue` (returning the `*mut RDKafkaQueue` this
+    // consumer's messages arrive on) and `rdsys::rd_kafka_queue_cb_event_enable` (the librdkafka
+    // binding that registers/unregisters a wakeup callback on that queue), neither of which is
+    // introduced by this file. If either isn't already part of `BaseConsumer`'s/rdkafka-sys's
+    // surface, this won't link — confirm both exist before merging.
+    fn new<C: ConsumerContext>(consumer: &BaseConsumer<C>) -> Arc<WakeupContext> {
+        let queue = consumer.get_consumer_queue();
+        let wakeup = Arc::new(WakeupContext { queue, tasks: Mutex::new(HashMap::new()) });
+        let opaque = &*wakeup as *const WakeupContext as *mut c_void;
+        unsafe {
+            rdsys::rd_kafka_queue_cb_event_enable(queue, Some(wakeup_cb), opaque);
+        }
+        wakeup
+    }
+
+    fn register(&self, stream_id: usize, task: Task) {
+        self.tasks.lock().unwrap().insert(stream_id, task);
+    }
+
+    fn unregister(&self, stream_id: usize) {
+        self.tasks.lock().unwrap().remove(&stream_id);
+    }
+}
+
+impl Drop for WakeupContext {
+    fn drop(&mut self) {
+        // Unhook the callback before the consumer (and the queue it owns) goes away: once this
+        // returns, librdkafka will no longer call `wakeup_cb` with our (about to be freed)
+        // `WakeupContext` pointer.
+        unsafe {
+            rdsys::rd_kafka_queue_cb_event_enable(self.queue, None, ptr::null_mut());
+        }
+    }
+}
+
+unsafe extern "C" fn wakeup_cb(opaque: *mut c_void) {
+    let wakeup = &*(opaque as *const WakeupContext);
+    // Wake every registered stream (see the struct doc comment for why waking only one is
+    // unsafe for correctness). Each woken stream re-registers its task on its next `poll`.
+    for (_, task) in wakeup.tasks.lock().unwrap().drain() {
+        task.notify();
     }
 }
 
@@ -85,115 +281,316 @@ impl Drop for PolledPtr {
 unsafe impl Send for PolledPtr {}
 
 
-/// A Stream of Kafka messages. It can be used to receive messages as they are received.
-pub struct MessageStream<'a, C: ConsumerContext + 'static> {
-    consumer: &'a StreamConsumer<C>,
-    receiver: mpsc::Receiver<KafkaResult<PolledPtr>>,
+/// Default buffer watermarks used by `start`/`start_with`/`stream`: a `high_watermark` of `0`
+/// disables prefetching, so a `MessageStream` only ever holds the one message it was just asked
+/// for and never buffers messages ahead of demand. This keeps the default fully demand-driven,
+/// so dropping the stream can never discard an already-consumed-but-undelivered message. Pass an
+/// explicit `high_watermark` > 0 via `start_with_backpressure` to opt into prefetching instead.
+const DEFAULT_HIGH_WATERMARK: usize = 0;
+const DEFAULT_LOW_WATERMARK: usize = 0;
+
+/// How often a `MessageStream` re-checks for data on its own, independently of `wakeup_cb`. This
+/// serves two purposes: it's the cadence at which `no_message_error` streams surface another
+/// `KafkaError::NoMessageReceived`, and — more importantly — it's a safety net for every stream,
+/// `no_message_error` or not. `wakeup_cb` is the fast path, but this poll is what guarantees
+/// forward progress if a wakeup is ever missed (or, short of the FFI surface it depends on being
+/// confirmed, never fires at all): without it, a stream would wait on `NotReady` indefinitely.
+/// Without *some* delay here, a loop that gets `NotReady` from librdkafka but `Ready` from us
+/// would also busy-spin at 100% CPU, since `poll_raw(0)` returns instantly.
+const POLL_FALLBACK_INTERVAL_MS: u64 = 100;
+
+/// A Stream of Kafka messages. Every call to `poll` pulls directly from the underlying
+/// `BaseConsumer`, so a message returned by the stream has genuinely been handed to the
+/// application. Several `MessageStream`s can be alive at the same time for the same
+/// `StreamConsumer`: a message consumed from the broker is delivered to exactly one of them, and
+/// seeking the consumer repositions all of them, since they all share the same `BaseConsumer`.
+///
+/// With `high_watermark == 0` (the default), the stream is fully demand-driven: it fetches at
+/// most the one message needed to satisfy the current `poll`, so it never holds an
+/// already-consumed message that could be discarded undelivered if the stream is dropped. A
+/// `high_watermark` > 0 (see `StreamConsumer::start_with_backpressure`) trades away that
+/// guarantee for throughput: the stream may then buffer up to `high_watermark` messages ahead of
+/// demand, and any of them still buffered when the stream is dropped are destroyed without ever
+/// reaching the application, even though they were already consumed from the broker.
+pub struct MessageStream<'a, C: ConsumerContext + 'static, R: Runtime = DefaultRuntime> {
+    consumer: &'a StreamConsumer<C, R>,
+    stream_id: usize,
+    no_message_error: bool,
+    poll_delay: Option<R::Delay>,
+    buffer: VecDeque<KafkaResult<PolledPtr>>,
+    high_watermark: usize,
+    low_watermark: usize,
+    paused: bool,
 }
 
-impl<'a, C: ConsumerContext + 'static> MessageStream<'a, C> {
-    fn new(consumer: &'a StreamConsumer<C>, receiver: mpsc::Receiver<KafkaResult<PolledPtr>>) -> MessageStream<'a, C> {
+impl<'a, C: ConsumerContext + 'static, R: Runtime> MessageStream<'a, C, R> {
+    fn new(
+        consumer: &'a StreamConsumer<C, R>,
+        no_message_error: bool,
+        high_watermark: usize,
+        low_watermark: usize,
+    ) -> MessageStream<'a, C, R> {
+        let stream_id = consumer.next_stream_id.fetch_add(1, Ordering::Relaxed);
         MessageStream {
-            consumer: consumer,
-            receiver: receiver,
+            consumer,
+            stream_id,
+            no_message_error,
+            poll_delay: None,
+            buffer: VecDeque::new(),
+            high_watermark,
+            low_watermark,
+            paused: false,
+        }
+    }
+
+    fn poll_raw(&self) -> KafkaResult<Option<PolledPtr>> {
+        self.consumer.consumer.poll_raw(0).map(|opt| opt.map(PolledPtr::new))
+    }
+
+    /// Tops up the buffer from `poll_raw` while under the high watermark, unless polling is
+    /// currently paused waiting for the buffer to drain down to the low watermark. Only called
+    /// when `high_watermark > 0`; see `poll` for the (unbuffered) `high_watermark == 0` path.
+    fn fill_buffer(&mut self) {
+        if self.paused && self.buffer.len() <= self.low_watermark {
+            self.paused = false;
+        }
+        if self.paused {
+            return;
+        }
+        while self.buffer.len() < self.high_watermark {
+            match self.poll_raw() {
+                Ok(Some(polled_ptr)) => self.buffer.push_back(Ok(polled_ptr)),
+                Ok(None) => break,
+                Err(e) => {
+                    self.buffer.push_back(Err(e));
+                    break;
+                },
+            }
+        }
+        if self.buffer.len() >= self.high_watermark {
+            self.paused = true;
+        }
+    }
+
+    /// Pulls the single next polled message/error, if any, without touching `self.buffer`. Used
+    /// for the `high_watermark == 0` (demand-driven, default) path, so the stream never retains a
+    /// message it hasn't handed to `poll`'s caller yet.
+    fn poll_one(&self) -> Option<KafkaResult<PolledPtr>> {
+        match self.poll_raw() {
+            Ok(Some(polled_ptr)) => Some(Ok(polled_ptr)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
         }
     }
 }
 
-impl<'a, C: ConsumerContext + 'a> Stream for MessageStream<'a, C> {
+impl<'a, C: ConsumerContext + 'static, R: Runtime> Drop for MessageStream<'a, C, R> {
+    fn drop(&mut self) {
+        self.consumer.wakeup.unregister(self.stream_id);
+    }
+}
+
+impl<'a, C: ConsumerContext + 'a, R: Runtime> Stream for MessageStream<'a, C, R> {
     type Item = KafkaResult<Message<'a>>;
     type Error = ();
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        match self.receiver.poll() {
-            Ok(async) => Ok(async.map(|option|
-                option.map(|result|
-                    result.map(|polled_ptr| polled_ptr.into_message_of(self.consumer))))),
-            Err(e) => Err(e),
-        }
-    }
-}
-
-impl<C: ConsumerContext> StreamConsumer<C> {
-    /// Starts the StreamConsumer with default configuration (100ms polling interval and no
-    /// `NoMessageReceived` notifications).
-    pub fn start(&self) -> MessageStream<C> {
-        self.start_with(Duration::from_millis(100), false)
-    }
-
-    /// Starts the StreamConsumer with the specified poll interval. Additionally, if
-    /// `no_message_error` is set to true, it will return an error of type
-    /// `KafkaError::NoMessageReceived` every time the poll interval is reached and no message
-    /// has been received.
-    pub fn start_with(&self, poll_interval: Duration, no_message_error: bool) -> MessageStream<C> {
-        let (sender, receiver) = mpsc::channel(0);
-        let consumer = self.consumer.clone();
-        let should_stop = self.should_stop.clone();
-        let handle = thread::Builder::new()
-            .name("poll".to_string())
-            .spawn(move || {
-                poll_loop(consumer, sender, should_stop, poll_interval, no_message_error);
-            })
-            .expect("Failed to start polling thread");
-        self.handle.set(Some(handle));
-        MessageStream::new(self, receiver)
-    }
-
-    /// Stops the StreamConsumer, blocking the caller until the internal consumer
-    /// has been stopped.
-    pub fn stop(&mut self) {
-        if let Some(handle) = self.handle.take() {
-            trace!("Stopping polling");
-            self.should_stop.store(true, Ordering::Relaxed);
-            trace!("Waiting for polling thread termination");
-            match handle.join() {
-                Ok(()) => trace!("Polling stopped"),
-                Err(e) => warn!("Failure while terminating thread: {:?}", e),
+        // Register interest before polling, so a wakeup that fires between the registration
+        // and the poll below isn't lost: the worst case is a spurious, harmless re-poll.
+        self.consumer.wakeup.register(self.stream_id, task::current());
+        loop {
+            let polled = if self.high_watermark == 0 {
+                self.poll_one()
+            } else {
+                self.fill_buffer();
+                self.buffer.pop_front()
             };
+            match polled {
+                Some(Ok(polled_ptr)) => {
+                    self.poll_delay = None;
+                    return Ok(Async::Ready(Some(Ok(polled_ptr.into_message_of(self.consumer)))));
+                },
+                Some(Err(e)) => {
+                    self.poll_delay = None;
+                    return Ok(Async::Ready(Some(Err(e))));
+                },
+                None => match self.poll_delay.take() {
+                    Some(mut delay) => match delay.poll() {
+                        Ok(Async::Ready(())) | Err(()) => {
+                            if self.no_message_error {
+                                return Ok(Async::Ready(Some(Err(KafkaError::NoMessageReceived))));
+                            }
+                            // The fallback tick fired with nothing to report: `wakeup_cb` may
+                            // simply not have fired yet, or (until the FFI surface it depends on
+                            // is confirmed, see `WakeupContext::new`) may never fire at all. Loop
+                            // back around and poll again ourselves rather than trusting it alone.
+                            continue;
+                        },
+                        Ok(Async::NotReady) => {
+                            self.poll_delay = Some(delay);
+                            return Ok(Async::NotReady);
+                        },
+                    },
+                    None => {
+                        // Loop back so the new delay gets polled, which registers this task to be
+                        // woken when the timer fires.
+                        self.poll_delay = Some(self.consumer.runtime.delay_for(Duration::from_millis(POLL_FALLBACK_INTERVAL_MS)));
+                        continue;
+                    },
+                },
+            }
+        }
+    }
+}
+
+impl<C: ConsumerContext, R: Runtime> StreamConsumer<C, R> {
+    /// Starts the `StreamConsumer`, returning a `Stream` that can be polled to receive messages.
+    /// No background thread is spawned: messages are pulled from librdkafka as the stream itself
+    /// is polled, and the task is woken up as soon as librdkafka has new data.
+    pub fn start(&self) -> MessageStream<C, R> {
+        self.start_with(false)
+    }
+
+    /// Like `start`, but if `no_message_error` is set to true, the stream surfaces an error of
+    /// type `KafkaError::NoMessageReceived` after roughly `POLL_FALLBACK_INTERVAL_MS` of silence,
+    /// rather than just returning `NotReady` for as long as no message is available.
+    pub fn start_with(&self, no_message_error: bool) -> MessageStream<C, R> {
+        MessageStream::new(self, no_message_error, DEFAULT_HIGH_WATERMARK, DEFAULT_LOW_WATERMARK)
+    }
+
+    /// Like `start_with`, but lets the buffer used to prefetch messages ahead of demand be sized
+    /// explicitly: the stream keeps polling and buffering while it holds fewer than
+    /// `high_watermark` messages, and pauses polling once it reaches `high_watermark`, resuming
+    /// only once the buffer has drained back down to `low_watermark`. A `high_watermark` of `0`
+    /// (the default used by `start`/`start_with`) disables prefetching entirely.
+    ///
+    /// Trading demand-driven consumption for a prefetch buffer (`high_watermark > 0`) reopens the
+    /// message-loss window manual offset storing is meant to close: a message already consumed
+    /// from the broker can sit in the buffer, and is destroyed without reaching the application
+    /// if the `MessageStream` is dropped before it's pulled out. Only raise `high_watermark` if
+    /// that tradeoff is acceptable for the throughput it buys.
+    pub fn start_with_backpressure(
+        &self,
+        no_message_error: bool,
+        high_watermark: usize,
+        low_watermark: usize,
+    ) -> MessageStream<C, R> {
+        MessageStream::new(self, no_message_error, high_watermark, low_watermark)
+    }
+
+    /// Returns a new `MessageStream` reading from this consumer. Can be called repeatedly to fan
+    /// out consumption of a single subscription across several tasks or threads: each polled
+    /// message still goes to only one of the live streams.
+    pub fn stream(&self) -> MessageStream<C, R> {
+        MessageStream::new(self, false, DEFAULT_HIGH_WATERMARK, DEFAULT_LOW_WATERMARK)
+    }
+
+    /// Starts the `StreamConsumer`, returning a `Stream` whose items are batches of up to
+    /// `max_batch_size` messages. A batch is emitted as soon as it's full, or as soon as
+    /// `batch_timeout` has elapsed since the first message of the batch was received, whichever
+    /// comes first: messages are never held past `batch_timeout` waiting for the batch to fill.
+    /// This amortizes per-message overhead for high-throughput sinks that can commit once per
+    /// batch instead of once per message.
+    pub fn start_batched(&self, max_batch_size: usize, batch_timeout: Duration) -> BatchedMessageStream<C, R> {
+        BatchedMessageStream {
+            inner: self.stream(),
+            runtime: &self.runtime,
+            max_batch_size,
+            batch_timeout,
+            batch: Vec::new(),
+            delay: None,
+        }
+    }
+
+    /// Gracefully shuts down the consumer: unsubscribes, unassigns all partitions, and drains any
+    /// events still buffered on the consumer queue (bounded by `DEFAULT_CLOSE_DRAIN_TIMEOUT_MS`),
+    /// so no partitions are held and no `RDKafkaMessage` pointers leak. Called automatically from
+    /// `Drop`; safe to call more than once. Use `close_with_timeout` directly if the default drain
+    /// bound isn't enough to reliably drain this consumer's queue.
+    pub fn close(&self) {
+        self.close_with_timeout(Duration::from_millis(DEFAULT_CLOSE_DRAIN_TIMEOUT_MS));
+    }
+
+    /// Like `close`, but lets the queue-drain bound be set explicitly. `close` (and so `Drop`)
+    /// uses `DEFAULT_CLOSE_DRAIN_TIMEOUT_MS`, which is short because it runs on every drop; pass a
+    /// longer `drain_timeout` here if the caller can afford to block longer for a more complete
+    /// drain, e.g. during an explicit, planned shutdown rather than an incidental drop.
+    pub fn close_with_timeout(&self, drain_timeout: Duration) {
+        trace!("Shutting down StreamConsumer");
+        if let Err(e) = self.unsubscribe() {
+            warn!("Failed to unsubscribe while closing consumer: {:?}", e);
+        }
+        if let Err(e) = self.unassign() {
+            warn!("Failed to unassign while closing consumer: {:?}", e);
+        }
+        let deadline = Instant::now() + drain_timeout;
+        while Instant::now() < deadline {
+            match self.consumer.poll_raw(CLOSE_DRAIN_POLL_INTERVAL_MS) {
+                Ok(Some(message_ptr)) => unsafe { rdsys::rd_kafka_message_destroy(message_ptr) },
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Error while draining consumer queue on close: {:?}", e);
+                    break;
+                },
+            }
         }
     }
 }
 
-impl<C: ConsumerContext> Drop for StreamConsumer<C> {
+impl<C: ConsumerContext, R: Runtime> Drop for StreamConsumer<C, R> {
     fn drop(&mut self) {
-        trace!("Destroy StreamConsumer");
-        // The polling thread must be fully stopped before we can proceed with the actual drop,,
-        // otherwise it might consume from a destroyed consumer.
-        self.stop();
+        self.close();
     }
 }
 
-/// Internal consumer loop.
-fn poll_loop<C: ConsumerContext>(
-    consumer: Arc<BaseConsumer<C>>,
-    sender: mpsc::Sender<KafkaResult<PolledPtr>>,
-    should_stop: Arc<AtomicBool>,
-    poll_interval: Duration,
-    no_message_error: bool,
-) {
-    trace!("Polling thread loop started");
-    let mut curr_sender = sender;
-    let poll_interval_ms = duration_to_millis(poll_interval) as i32;
-    while !should_stop.load(Ordering::Relaxed) {
-        trace!("Polling base consumer");
-        let future_sender = match consumer.poll_raw(poll_interval_ms) {
-            Ok(None) => {
-                if no_message_error {
-                    curr_sender.send(Err(KafkaError::NoMessageReceived))
-                } else {
-                    continue // TODO: check stream closed
+/// A `Stream` of message batches produced by `StreamConsumer::start_batched`.
+pub struct BatchedMessageStream<'a, C: ConsumerContext + 'static, R: Runtime = DefaultRuntime> {
+    inner: MessageStream<'a, C, R>,
+    runtime: &'a R,
+    max_batch_size: usize,
+    batch_timeout: Duration,
+    batch: Vec<KafkaResult<Message<'a>>>,
+    delay: Option<R::Delay>,
+}
+
+impl<'a, C: ConsumerContext + 'a, R: Runtime> Stream for BatchedMessageStream<'a, C, R> {
+    type Item = Vec<KafkaResult<Message<'a>>>;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            // Check the batch timeout before pulling the next message: otherwise, under
+            // sustained throughput, `inner.poll()` keeps returning `Ready(Some(_))` and this
+            // loop never reaches the `NotReady` arm, holding messages well past `batch_timeout`.
+            if let Some(mut delay) = self.delay.take() {
+                match delay.poll() {
+                    Ok(Async::Ready(())) | Err(()) => {
+                        // Batch timeout elapsed: flush whatever we have, even partial.
+                        return Ok(Async::Ready(Some(mem::replace(&mut self.batch, Vec::new()))));
+                    },
+                    Ok(Async::NotReady) => self.delay = Some(delay),
                 }
-            },
-            Ok(Some(m_ptr)) => curr_sender.send(Ok(PolledPtr::new(m_ptr))),
-            Err(e) => curr_sender.send(Err(e)),
-        };
-        match future_sender.wait() {
-            Ok(new_sender) => curr_sender = new_sender,
-            Err(e) => {
-                debug!("Sender not available: {:?}", e);
-                break;
             }
-        };
+            match self.inner.poll()? {
+                Async::Ready(Some(item)) => {
+                    if self.batch.is_empty() {
+                        self.delay = Some(self.runtime.delay_for(self.batch_timeout));
+                    }
+                    self.batch.push(item);
+                    if self.batch.len() >= self.max_batch_size {
+                        self.delay = None;
+                        return Ok(Async::Ready(Some(mem::replace(&mut self.batch, Vec::new()))));
+                    }
+                },
+                Async::Ready(None) => {
+                    return if self.batch.is_empty() {
+                        Ok(Async::Ready(None))
+                    } else {
+                        Ok(Async::Ready(Some(mem::replace(&mut self.batch, Vec::new()))))
+                    };
+                },
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
     }
-    trace!("Polling thread loop terminated");
 }